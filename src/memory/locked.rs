@@ -1,10 +1,35 @@
 use super::DeviceCopy;
 use error::*;
-use memory::{cuda_free_locked, cuda_malloc_locked};
+use memory::device::DeviceBuffer;
+use memory::{
+    cuda_free_locked, cuda_malloc_locked, cuda_memcpy_dtoh_async, cuda_memcpy_htod_async,
+    AsyncCopyDestination, DevicePointer,
+};
 use std::mem;
 use std::ops;
+use std::os::raw::c_void;
 use std::ptr;
 use std::slice;
+use stream::Stream;
+
+bitflags! {
+    /// Bit flags controlling how [`LockedBuffer::with_flags`](struct.LockedBuffer.html#method.with_flags)
+    /// allocates page-locked host memory. These map directly onto the flags accepted by the
+    /// `cuMemHostAlloc` driver call.
+    pub struct LockedFlags: u32 {
+        /// The memory is considered pinned by all CUDA contexts, not just the one that was
+        /// current when the allocation was made.
+        const PORTABLE = 0x01;
+        /// Map the allocation into the CUDA address space, allowing a kernel to read and write
+        /// the host-pinned memory directly without an explicit copy. See
+        /// [`LockedBuffer::device_pointer`](struct.LockedBuffer.html#method.device_pointer).
+        const DEVICEMAP = 0x02;
+        /// Allocate write-combined memory. Writes from the host are not cached and are flushed
+        /// across the PCIe bus faster, but the host reads back from this memory very slowly, so
+        /// it is best suited to upload-only staging buffers.
+        const WRITE_COMBINED = 0x04;
+    }
+}
 
 /// Fixed-size host-side buffer in page-locked memory. See the
 /// [`module-level documentation`](../memory/index.html) for more details on page-locked memory.
@@ -12,6 +37,7 @@ use std::slice;
 pub struct LockedBuffer<T: DeviceCopy> {
     buf: *mut T,
     capacity: usize,
+    flags: LockedFlags,
 }
 impl<T: DeviceCopy> LockedBuffer<T> {
     /// Allocate a new page-locked buffer large enough to hold `size` `T`'s and initialized with
@@ -98,9 +124,114 @@ impl<T: DeviceCopy> LockedBuffer<T> {
         Ok(LockedBuffer {
             buf: ptr as *mut T,
             capacity: size,
+            flags: LockedFlags::empty(),
         })
     }
 
+    /// Allocate a new page-locked buffer large enough to hold `size` `T`'s, using `flags` to
+    /// control the allocation behavior, but without initializing the contents.
+    ///
+    /// # Errors:
+    ///
+    /// If the allocation fails, returns the error from CUDA. If `size` is large enough that
+    /// `size * mem::sizeof::<T>()` overflows usize, then returns InvalidMemoryAllocation.
+    ///
+    /// # Safety:
+    ///
+    /// The caller must ensure that the contents of the buffer are initialized before reading from
+    /// the buffer.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use rustacuda::memory::*;
+    /// let mut buffer = unsafe {
+    ///     LockedBuffer::with_flags(5, LockedFlags::WRITE_COMBINED).unwrap()
+    /// };
+    /// for i in buffer.iter_mut() {
+    ///     *i = 0u64;
+    /// }
+    /// ```
+    pub unsafe fn with_flags(size: usize, flags: LockedFlags) -> CudaResult<Self> {
+        let bytes = size.checked_mul(mem::size_of::<T>())
+            .ok_or(CudaError::InvalidMemoryAllocation)?;
+
+        let ptr: *mut T = if bytes > 0 {
+            cuda_malloc_locked_with_flags(bytes, flags)? as *mut T
+        } else {
+            ptr::NonNull::dangling().as_ptr()
+        };
+        Ok(LockedBuffer {
+            buf: ptr,
+            capacity: size,
+            flags,
+        })
+    }
+
+    /// Returns the device-space pointer corresponding to this buffer's host-pinned memory, for
+    /// use by a kernel that wants to read or write this buffer directly without a `copy_to`/
+    /// `copy_from`.
+    ///
+    /// # Errors:
+    ///
+    /// Returns `CudaError::NotMapped` if this buffer was not allocated with
+    /// [`LockedFlags::DEVICEMAP`](struct.LockedFlags.html#associatedconstant.DEVICEMAP) (via
+    /// [`with_flags`](struct.LockedBuffer.html#method.with_flags)), without making a driver call.
+    /// A buffer created with [`from_raw_parts`](struct.LockedBuffer.html#method.from_raw_parts)
+    /// does not record its allocation flags and always returns `CudaError::NotMapped`.
+    /// Otherwise returns whatever error CUDA reports.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use rustacuda::memory::*;
+    /// let buffer = unsafe {
+    ///     LockedBuffer::with_flags(5, LockedFlags::DEVICEMAP).unwrap()
+    /// };
+    /// let device_ptr = buffer.device_pointer().unwrap();
+    /// ```
+    pub fn device_pointer(&self) -> CudaResult<DevicePointer<T>> {
+        if !self.flags.contains(LockedFlags::DEVICEMAP) {
+            return Err(CudaError::NotMapped);
+        }
+        cuda_host_get_device_pointer(self.buf)
+    }
+
+    /// Copies the contents of this buffer into a new `Vec`.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use rustacuda::memory::*;
+    /// let buffer = LockedBuffer::from_slice(&[1u64, 2, 3]).unwrap();
+    /// assert_eq!(vec![1u64, 2, 3], buffer.to_vec());
+    /// ```
+    pub fn to_vec(&self) -> Vec<T> {
+        self.as_slice().to_vec()
+    }
+
+    /// Allocate a new page-locked buffer and move the contents of `vec` into it.
+    ///
+    /// # Errors:
+    ///
+    /// If the allocation fails, returns the error from CUDA.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use rustacuda::memory::*;
+    /// let buffer = LockedBuffer::from_vec(vec![1u64, 2, 3]).unwrap();
+    /// assert_eq!(&[1u64, 2, 3], buffer.as_slice());
+    /// ```
+    pub fn from_vec(mut vec: Vec<T>) -> CudaResult<Self> {
+        unsafe {
+            let mut uninit = LockedBuffer::uninitialized(vec.len())?;
+            ptr::copy_nonoverlapping(vec.as_ptr(), uninit.as_mut_ptr(), vec.len());
+            vec.set_len(0);
+            Ok(uninit)
+        }
+    }
+
     /// Extracts a slice containing the entire buffer.
     ///
     /// Equivalent to `&s[..]`.
@@ -172,10 +303,29 @@ impl<T: DeviceCopy> LockedBuffer<T> {
         LockedBuffer {
             buf: ptr,
             capacity: size,
+            flags: LockedFlags::empty(),
         }
     }
 }
 
+fn cuda_malloc_locked_with_flags(bytes: usize, flags: LockedFlags) -> CudaResult<*mut c_void> {
+    use cuda_sys::cuda::cuMemHostAlloc;
+    unsafe {
+        let mut ptr: *mut c_void = ptr::null_mut();
+        cuMemHostAlloc(&mut ptr as *mut *mut c_void, bytes, flags.bits()).to_result()?;
+        Ok(ptr)
+    }
+}
+
+fn cuda_host_get_device_pointer<T>(host_ptr: *mut T) -> CudaResult<DevicePointer<T>> {
+    use cuda_sys::cuda::cuMemHostGetDevicePointer_v2;
+    unsafe {
+        let mut raw = 0u64;
+        cuMemHostGetDevicePointer_v2(&mut raw as *mut u64, host_ptr as *mut c_void, 0).to_result()?;
+        Ok(DevicePointer::wrap(raw as *mut T))
+    }
+}
+
 impl<T: DeviceCopy> AsRef<[T]> for LockedBuffer<T> {
     fn as_ref(&self) -> &[T] {
         self
@@ -216,6 +366,110 @@ impl<T: DeviceCopy> Drop for LockedBuffer<T> {
     }
 }
 
+impl<T: DeviceCopy> ::std::convert::TryFrom<Vec<T>> for LockedBuffer<T> {
+    type Error = CudaError;
+
+    fn try_from(vec: Vec<T>) -> CudaResult<Self> {
+        LockedBuffer::from_vec(vec)
+    }
+}
+
+/// An iterator that moves each element out of a [`LockedBuffer`](struct.LockedBuffer.html),
+/// freeing the underlying page-locked allocation when the iterator is dropped.
+#[derive(Debug)]
+pub struct IntoIter<T: DeviceCopy> {
+    buf: *mut T,
+    capacity: usize,
+    index: usize,
+}
+impl<T: DeviceCopy> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.index >= self.capacity {
+            None
+        } else {
+            let item = unsafe { ptr::read(self.buf.add(self.index)) };
+            self.index += 1;
+            Some(item)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.capacity - self.index;
+        (remaining, Some(remaining))
+    }
+}
+impl<T: DeviceCopy> ExactSizeIterator for IntoIter<T> {}
+impl<T: DeviceCopy> Drop for IntoIter<T> {
+    fn drop(&mut self) {
+        // Drop any elements that were never yielded, then free the backing allocation.
+        for i in self.index..self.capacity {
+            unsafe {
+                ptr::drop_in_place(self.buf.add(i));
+            }
+        }
+        if self.capacity > 0 && mem::size_of::<T>() > 0 {
+            unsafe {
+                cuda_free_locked(self.buf).expect("Failed to deallocate CUDA page-locked memory.");
+            }
+        }
+    }
+}
+impl<T: DeviceCopy> IntoIterator for LockedBuffer<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        let buf = self.buf;
+        let capacity = self.capacity;
+        // Ownership of `buf` is transferred to the `IntoIter`, which is now responsible for
+        // dropping the remaining elements and freeing the allocation.
+        mem::forget(self);
+        IntoIter {
+            buf,
+            capacity,
+            index: 0,
+        }
+    }
+}
+
+impl<T: DeviceCopy> AsyncCopyDestination<DeviceBuffer<T>> for LockedBuffer<T> {
+    /// # Safety
+    ///
+    /// `self` must not be read, written, moved, or dropped until `stream` has been synchronized
+    /// - the CUDA driver may still be copying into this buffer's pinned memory after this call
+    /// returns.
+    unsafe fn async_copy_from(&mut self, source: &DeviceBuffer<T>, stream: &Stream) -> CudaResult<()> {
+        assert!(
+            self.len() == source.len(),
+            "destination and source length mismatch"
+        );
+        let size = mem::size_of::<T>() * self.capacity;
+        if size != 0 {
+            cuda_memcpy_dtoh_async(self.buf, source.as_device_ptr(), size, stream)?;
+        }
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// `self` must not be read, written, moved, or dropped until `stream` has been synchronized
+    /// - the CUDA driver may still be reading from this buffer's pinned memory after this call
+    /// returns.
+    unsafe fn async_copy_to(&self, dest: &mut DeviceBuffer<T>, stream: &Stream) -> CudaResult<()> {
+        assert!(
+            self.len() == dest.len(),
+            "destination and source length mismatch"
+        );
+        let size = mem::size_of::<T>() * self.capacity;
+        if size != 0 {
+            cuda_memcpy_htod_async(dest.as_device_ptr(), self.buf, size, stream)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -271,4 +525,81 @@ mod test {
         let err = LockedBuffer::new(&0u64, ::std::usize::MAX - 1).unwrap_err();
         assert_eq!(CudaError::InvalidMemoryAllocation, err);
     }
+
+    #[test]
+    fn test_with_flags_devicemap() {
+        let mut buffer = unsafe {
+            LockedBuffer::with_flags(5, LockedFlags::DEVICEMAP).unwrap()
+        };
+        buffer[0] = 1;
+        let _device_ptr = buffer.device_pointer().unwrap();
+    }
+
+    #[test]
+    fn test_device_pointer_without_devicemap_flag() {
+        let buffer = LockedBuffer::new(&0u64, 5).unwrap();
+        let err = buffer.device_pointer().unwrap_err();
+        assert_eq!(CudaError::NotMapped, err);
+    }
+
+    #[test]
+    fn test_with_flags_write_combined() {
+        let buffer = unsafe {
+            LockedBuffer::<u64>::with_flags(5, LockedFlags::WRITE_COMBINED).unwrap()
+        };
+        drop(buffer);
+    }
+
+    #[test]
+    fn test_to_vec() {
+        let buffer = LockedBuffer::from_slice(&[1u64, 2, 3]).unwrap();
+        assert_eq!(vec![1u64, 2, 3], buffer.to_vec());
+    }
+
+    #[test]
+    fn test_from_vec() {
+        let buffer = LockedBuffer::from_vec(vec![1u64, 2, 3]).unwrap();
+        assert_eq!(&[1u64, 2, 3], buffer.as_slice());
+    }
+
+    #[test]
+    fn test_try_from_vec() {
+        use std::convert::TryFrom;
+        let buffer = LockedBuffer::try_from(vec![1u64, 2, 3]).unwrap();
+        assert_eq!(&[1u64, 2, 3], buffer.as_slice());
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let buffer = LockedBuffer::from_slice(&[1u64, 2, 3]).unwrap();
+        let collected: Vec<u64> = buffer.into_iter().collect();
+        assert_eq!(vec![1u64, 2, 3], collected);
+    }
+
+    #[test]
+    fn test_into_iter_partial_consume() {
+        let buffer = LockedBuffer::from_slice(&[1u64, 2, 3]).unwrap();
+        let mut iter = buffer.into_iter();
+        assert_eq!(Some(1), iter.next());
+        // Remaining elements are dropped along with the allocation when `iter` goes out of scope.
+    }
+
+    #[test]
+    fn test_async_copy_round_trip() {
+        use memory::device::DeviceBuffer;
+        use stream::{Stream, StreamFlags};
+
+        let stream = Stream::new(StreamFlags::NON_BLOCKING, None).unwrap();
+        let mut host_in = LockedBuffer::from_slice(&[1u64, 2, 3, 4, 5]).unwrap();
+        let mut device = unsafe { DeviceBuffer::uninitialized(5) }.unwrap();
+        let mut host_out = LockedBuffer::new(&0u64, 5).unwrap();
+
+        unsafe {
+            host_in.async_copy_to(&mut device, &stream).unwrap();
+            host_out.async_copy_from(&device, &stream).unwrap();
+        }
+        stream.synchronize().unwrap();
+
+        assert_eq!(host_in.as_slice(), host_out.as_slice());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,164 @@
+pub mod locked;
+pub mod unified;
+
+pub use self::locked::LockedBuffer;
+pub use self::locked::LockedFlags;
+pub use self::unified::{MemAdvise, UnifiedBox, UnifiedBuffer};
+
+use error::*;
+use std::os::raw::c_void;
+use std::ptr;
+use stream::Stream;
+
+/// Marker trait for types which can safely be copied to or from a CUDA device.
+///
+/// A type must be safe to duplicate bit-for-bit into device memory - no references or host
+/// pointers, no `Drop` impl. It is `unsafe` to implement because the compiler cannot check
+/// these requirements for you.
+pub unsafe trait DeviceCopy {}
+
+unsafe impl DeviceCopy for u8 {}
+unsafe impl DeviceCopy for u16 {}
+unsafe impl DeviceCopy for u32 {}
+unsafe impl DeviceCopy for u64 {}
+unsafe impl DeviceCopy for usize {}
+unsafe impl DeviceCopy for i8 {}
+unsafe impl DeviceCopy for i16 {}
+unsafe impl DeviceCopy for i32 {}
+unsafe impl DeviceCopy for i64 {}
+unsafe impl DeviceCopy for isize {}
+unsafe impl DeviceCopy for f32 {}
+unsafe impl DeviceCopy for f64 {}
+unsafe impl DeviceCopy for bool {}
+
+/// A pointer to device memory, tagged with the type of the data it points to.
+///
+/// `DevicePointer` is what the kernel-launch machinery accepts for a buffer argument - unlike a
+/// raw `*mut T`, it carries no implication that it is safe to dereference on the host.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct DevicePointer<T>(*mut T);
+unsafe impl<T> DeviceCopy for DevicePointer<T> {}
+impl<T> DevicePointer<T> {
+    /// Wraps a raw device pointer in a `DevicePointer`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a valid device pointer for `T`, or null.
+    pub unsafe fn wrap(ptr: *mut T) -> Self {
+        DevicePointer(ptr)
+    }
+
+    /// Returns the raw device pointer this `DevicePointer` wraps.
+    pub fn as_raw(&self) -> *const T {
+        self.0
+    }
+
+    /// Returns the raw device pointer this `DevicePointer` wraps.
+    pub fn as_raw_mut(&mut self) -> *mut T {
+        self.0
+    }
+}
+
+/// Allocates `bytes` of page-locked host memory via `cuMemAllocHost`.
+pub(crate) fn cuda_malloc_locked<T>(bytes: usize) -> CudaResult<*mut T> {
+    use cuda_sys::cuda::cuMemAllocHost_v2;
+    unsafe {
+        let mut ptr: *mut c_void = ptr::null_mut();
+        cuMemAllocHost_v2(&mut ptr as *mut *mut c_void, bytes).to_result()?;
+        Ok(ptr as *mut T)
+    }
+}
+
+/// Frees memory allocated with [`cuda_malloc_locked`](fn.cuda_malloc_locked.html).
+pub(crate) unsafe fn cuda_free_locked<T>(ptr: *mut T) -> CudaResult<()> {
+    use cuda_sys::cuda::cuMemFreeHost;
+    cuMemFreeHost(ptr as *mut c_void).to_result()?;
+    Ok(())
+}
+
+/// Allocates `bytes` of CUDA unified (managed) memory via `cuMemAllocManaged`, accessible from
+/// both the host and any device in the system.
+pub(crate) fn cuda_malloc_unified<T>(bytes: usize) -> CudaResult<*mut T> {
+    use cuda_sys::cuda::{cuMemAllocManaged, CU_MEM_ATTACH_GLOBAL};
+    unsafe {
+        let mut ptr: u64 = 0;
+        cuMemAllocManaged(&mut ptr as *mut u64, bytes, CU_MEM_ATTACH_GLOBAL).to_result()?;
+        Ok(ptr as *mut T)
+    }
+}
+
+/// Frees memory allocated with [`cuda_malloc_unified`](fn.cuda_malloc_unified.html).
+pub(crate) unsafe fn cuda_free_unified<T>(ptr: *mut T) -> CudaResult<()> {
+    use cuda_sys::cuda::cuMemFree_v2;
+    cuMemFree_v2(ptr as u64).to_result()?;
+    Ok(())
+}
+
+/// A trait describing async host/device memory copies queued against a
+/// [`Stream`](../stream/struct.Stream.html).
+///
+/// Unlike `CopyDestination`, these copies are only queued on the stream and may not have
+/// completed when the call returns.
+///
+/// # Safety
+///
+/// The memory region being copied to/from must not be read, written, moved, or dropped until the
+/// stream has been synchronized (or an event recorded after the copy has been waited on). The
+/// caller is responsible for keeping the region alive and untouched for the duration of the
+/// transfer; this is why every method on this trait is `unsafe`.
+pub trait AsyncCopyDestination<O: ?Sized> {
+    /// Asynchronously copies data from `source` into `self` on the given stream.
+    ///
+    /// # Safety
+    ///
+    /// `self` must not be read, written, moved, or dropped until `stream` has been synchronized.
+    unsafe fn async_copy_from(&mut self, source: &O, stream: &Stream) -> CudaResult<()>;
+
+    /// Asynchronously copies data from `self` into `dest` on the given stream.
+    ///
+    /// # Safety
+    ///
+    /// `self` and `dest` must not be read, written, moved, or dropped until `stream` has been
+    /// synchronized.
+    unsafe fn async_copy_to(&self, dest: &mut O, stream: &Stream) -> CudaResult<()>;
+}
+
+/// Asynchronously copies `size` bytes of host memory at `src` to device memory at `dest` on the
+/// given stream, via `cuMemcpyHtoDAsync`.
+pub(crate) fn cuda_memcpy_htod_async<T>(
+    dest: DevicePointer<T>,
+    src: *const T,
+    size: usize,
+    stream: &Stream,
+) -> CudaResult<()> {
+    use cuda_sys::cuda::cuMemcpyHtoDAsync_v2;
+    unsafe {
+        cuMemcpyHtoDAsync_v2(
+            dest.as_raw() as u64,
+            src as *const c_void,
+            size,
+            stream.as_inner(),
+        ).to_result()?;
+    }
+    Ok(())
+}
+
+/// Asynchronously copies `size` bytes of device memory at `src` to host memory at `dest` on the
+/// given stream, via `cuMemcpyDtoHAsync`.
+pub(crate) fn cuda_memcpy_dtoh_async<T>(
+    dest: *mut T,
+    src: DevicePointer<T>,
+    size: usize,
+    stream: &Stream,
+) -> CudaResult<()> {
+    use cuda_sys::cuda::cuMemcpyDtoHAsync_v2;
+    unsafe {
+        cuMemcpyDtoHAsync_v2(
+            dest as *mut c_void,
+            src.as_raw() as u64,
+            size,
+            stream.as_inner(),
+        ).to_result()?;
+    }
+    Ok(())
+}
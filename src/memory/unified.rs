@@ -0,0 +1,464 @@
+use super::DeviceCopy;
+use device::Device;
+use error::*;
+use memory::{cuda_free_unified, cuda_malloc_unified};
+use std::mem;
+use std::ops;
+use std::ptr;
+use std::slice;
+use stream::Stream;
+
+/// Advice to give the CUDA driver about how a unified allocation will be used, for use with
+/// [`UnifiedBuffer::advise`](struct.UnifiedBuffer.html#method.advise) and
+/// [`UnifiedBox::advise`](struct.UnifiedBox.html#method.advise). This is purely a performance
+/// hint - it never changes the correctness of accesses to the allocation, only how eagerly the
+/// driver migrates or replicates its pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemAdvise {
+    /// Mark the allocation as read-mostly. The driver may create a read-only copy of the pages
+    /// on the given device, avoiding migration as long as only reads occur from that device.
+    SetReadMostly,
+    /// Set the given device as the preferred location for the allocation's pages. This does not
+    /// cause an immediate migration, but subsequent migrations away from the device are avoided
+    /// where possible.
+    SetPreferredLocation,
+    /// Declare that the given device will be accessing the allocation, establishing a direct
+    /// mapping to it without necessarily migrating the data, which is most useful when the
+    /// device has fast interconnects (e.g. NVLink) to the page's current location.
+    SetAccessedBy,
+}
+
+fn cuda_mem_prefetch_async<T>(ptr: *const T, count: usize, device: &Device, stream: &Stream) -> CudaResult<()> {
+    use cuda_sys::cuda::cuMemPrefetchAsync;
+    unsafe {
+        cuMemPrefetchAsync(
+            ptr as u64,
+            count * mem::size_of::<T>(),
+            device.as_raw(),
+            stream.as_inner(),
+        ).to_result()?;
+    }
+    Ok(())
+}
+
+fn cuda_mem_prefetch_to_host_async<T>(ptr: *const T, count: usize, stream: &Stream) -> CudaResult<()> {
+    use cuda_sys::cuda::{cuMemPrefetchAsync, CU_DEVICE_CPU};
+    unsafe {
+        cuMemPrefetchAsync(
+            ptr as u64,
+            count * mem::size_of::<T>(),
+            CU_DEVICE_CPU,
+            stream.as_inner(),
+        ).to_result()?;
+    }
+    Ok(())
+}
+
+fn cuda_mem_advise<T>(ptr: *const T, count: usize, advice: MemAdvise, device: &Device) -> CudaResult<()> {
+    use cuda_sys::cuda::{
+        cuMemAdvise, CUmem_advise::*,
+    };
+    let raw_advice = match advice {
+        MemAdvise::SetReadMostly => CU_MEM_ADVISE_SET_READ_MOSTLY,
+        MemAdvise::SetPreferredLocation => CU_MEM_ADVISE_SET_PREFERRED_LOCATION,
+        MemAdvise::SetAccessedBy => CU_MEM_ADVISE_SET_ACCESSED_BY,
+    };
+    unsafe {
+        cuMemAdvise(
+            ptr as u64,
+            count * mem::size_of::<T>(),
+            raw_advice,
+            device.as_raw(),
+        ).to_result()?;
+    }
+    Ok(())
+}
+
+/// A pointer-sized box allocated in CUDA unified (managed) memory.
+///
+/// See the [`module-level documentation`](../memory/index.html) for more details on unified
+/// memory. Unlike [`DeviceBox`](struct.DeviceBox.html), the contents of a `UnifiedBox` can be
+/// read and written directly from the host, with the CUDA driver transparently migrating the
+/// backing pages between host and device as they are accessed.
+#[derive(Debug)]
+pub struct UnifiedBox<T: DeviceCopy> {
+    ptr: *mut T,
+}
+impl<T: DeviceCopy> UnifiedBox<T> {
+    /// Allocate unified memory and move `value` into it.
+    ///
+    /// # Errors:
+    ///
+    /// If the allocation fails, returns the error from CUDA.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use rustacuda::memory::*;
+    /// let mut x = UnifiedBox::new(5u64).unwrap();
+    /// *x = 10;
+    /// ```
+    pub fn new(value: T) -> CudaResult<Self> {
+        unsafe {
+            let uninit = UnifiedBox::uninitialized()?;
+            ptr::write(uninit.ptr, value);
+            Ok(uninit)
+        }
+    }
+
+    /// Allocate unified memory, but without initializing the contents.
+    ///
+    /// # Errors:
+    ///
+    /// If the allocation fails, returns the error from CUDA.
+    ///
+    /// # Safety:
+    ///
+    /// The caller must ensure that the contents of the box are initialized before reading from
+    /// it.
+    pub unsafe fn uninitialized() -> CudaResult<Self> {
+        let ptr = if mem::size_of::<T>() == 0 {
+            ptr::NonNull::dangling().as_ptr()
+        } else {
+            cuda_malloc_unified(mem::size_of::<T>())?
+        };
+        Ok(UnifiedBox { ptr })
+    }
+
+    /// Returns the device pointer to the managed allocation, for passing to a kernel launch.
+    pub fn as_device_ptr(&self) -> ::memory::DevicePointer<T> {
+        unsafe { ::memory::DevicePointer::wrap(self.ptr) }
+    }
+
+    /// Prefetch the allocation to `device` on the given stream, migrating its pages ahead of a
+    /// kernel launch to avoid page-fault thrashing during the kernel itself.
+    ///
+    /// # Errors:
+    ///
+    /// If the prefetch fails, returns the error from CUDA.
+    pub fn prefetch_to_device(&self, device: &Device, stream: &Stream) -> CudaResult<()> {
+        cuda_mem_prefetch_async(self.ptr, 1, device, stream)
+    }
+
+    /// Prefetch the allocation back to the host on the given stream.
+    ///
+    /// # Errors:
+    ///
+    /// If the prefetch fails, returns the error from CUDA.
+    pub fn prefetch_to_host(&self, stream: &Stream) -> CudaResult<()> {
+        cuda_mem_prefetch_to_host_async(self.ptr, 1, stream)
+    }
+
+    /// Advise the CUDA driver about how this allocation will be used. See
+    /// [`MemAdvise`](enum.MemAdvise.html) for the available hints.
+    ///
+    /// # Errors:
+    ///
+    /// If the advise call fails, returns the error from CUDA.
+    pub fn advise(&self, advice: MemAdvise, device: &Device) -> CudaResult<()> {
+        cuda_mem_advise(self.ptr, 1, advice, device)
+    }
+}
+impl<T: DeviceCopy> ops::Deref for UnifiedBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+impl<T: DeviceCopy> ops::DerefMut for UnifiedBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+impl<T: DeviceCopy> Drop for UnifiedBox<T> {
+    fn drop(&mut self) {
+        if mem::size_of::<T>() > 0 {
+            // No choice but to panic if this fails.
+            unsafe {
+                cuda_free_unified(self.ptr).expect("Failed to deallocate CUDA unified memory.");
+            }
+        }
+    }
+}
+
+/// Fixed-size buffer in CUDA unified (managed) memory.
+///
+/// See the [`module-level documentation`](../memory/index.html) for more details on unified
+/// memory. A `UnifiedBuffer` behaves like a [`LockedBuffer`](../memory/struct.LockedBuffer.html)
+/// in that it derefs directly to a host `&[T]`/`&mut [T]`, but the same allocation can also be
+/// passed straight to a kernel as a device pointer - the driver migrates pages between host and
+/// device on demand as each side touches them.
+#[derive(Debug)]
+pub struct UnifiedBuffer<T: DeviceCopy> {
+    buf: *mut T,
+    capacity: usize,
+}
+impl<T: DeviceCopy> UnifiedBuffer<T> {
+    /// Allocate a new unified buffer large enough to hold `size` `T`'s and initialized with
+    /// clones of `value`.
+    ///
+    /// # Errors:
+    ///
+    /// If the allocation fails, returns the error from CUDA. If `size` is large enough that
+    /// `size * mem::sizeof::<T>()` overflows usize, then returns InvalidMemoryAllocation.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use rustacuda::memory::*;
+    /// let mut buffer = UnifiedBuffer::new(&0u64, 5).unwrap();
+    /// buffer[0] = 1;
+    /// ```
+    pub fn new(value: &T, size: usize) -> CudaResult<Self> {
+        unsafe {
+            let mut uninit = UnifiedBuffer::uninitialized(size)?;
+            for x in 0..size {
+                *uninit.get_unchecked_mut(x) = value.clone();
+            }
+            Ok(uninit)
+        }
+    }
+
+    /// Allocate a new unified buffer of the same size as `slice`, initialized with a clone of the
+    /// data in `slice`.
+    ///
+    /// # Errors:
+    ///
+    /// If the allocation fails, returns the error from CUDA.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// use rustacuda::memory::*;
+    /// let values = [0u64; 5];
+    /// let mut buffer = UnifiedBuffer::from_slice(&values).unwrap();
+    /// buffer[0] = 1;
+    /// ```
+    pub fn from_slice(slice: &[T]) -> CudaResult<Self> {
+        unsafe {
+            let mut uninit = UnifiedBuffer::uninitialized(slice.len())?;
+            for (i, x) in slice.iter().enumerate() {
+                *uninit.get_unchecked_mut(i) = x.clone();
+            }
+            Ok(uninit)
+        }
+    }
+
+    /// Allocate a new unified buffer large enough to hold `size` `T`'s, but without initializing
+    /// the contents.
+    ///
+    /// # Errors:
+    ///
+    /// If the allocation fails, returns the error from CUDA. If `size` is large enough that
+    /// `size * mem::sizeof::<T>()` overflows usize, then returns InvalidMemoryAllocation.
+    ///
+    /// # Safety:
+    ///
+    /// The caller must ensure that the contents of the buffer are initialized before reading from
+    /// the buffer.
+    pub unsafe fn uninitialized(size: usize) -> CudaResult<Self> {
+        let bytes = size.checked_mul(mem::size_of::<T>())
+            .ok_or(CudaError::InvalidMemoryAllocation)?;
+
+        let ptr: *mut T = if bytes > 0 {
+            cuda_malloc_unified(bytes)?
+        } else {
+            ptr::NonNull::dangling().as_ptr()
+        };
+        Ok(UnifiedBuffer {
+            buf: ptr,
+            capacity: size,
+        })
+    }
+
+    /// Extracts a slice containing the entire buffer.
+    ///
+    /// Equivalent to `&s[..]`.
+    pub fn as_slice(&self) -> &[T] {
+        self
+    }
+
+    /// Extracts a mutable slice of the entire buffer.
+    ///
+    /// Equivalent to `&mut s[..]`.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        self
+    }
+
+    /// Creates a `UnifiedBuffer<T>` directly from the raw components of another unified buffer.
+    ///
+    /// # Safety
+    ///
+    /// This is highly unsafe, due to the number of invariants that aren't checked:
+    ///
+    /// * `ptr` needs to have been previously allocated via `UnifiedBuffer` or
+    /// [`cuda_malloc_unified`](fn.cuda_malloc_unified.html).
+    /// * `ptr`'s `T` needs to have the same size and alignment as it was allocated with.
+    /// * `capacity` needs to be the capacity that the pointer was allocated with.
+    ///
+    /// Violating these may cause problems like corrupting the CUDA driver's internal data
+    /// structures.
+    ///
+    /// The ownership of `ptr` is effectively transferred to the `UnifiedBuffer<T>` which may then
+    /// deallocate, reallocate or change the contents of memory pointed to by the pointer at will.
+    /// Ensure that nothing else uses the pointer after calling this function.
+    pub unsafe fn from_raw_parts(ptr: *mut T, size: usize) -> UnifiedBuffer<T> {
+        UnifiedBuffer {
+            buf: ptr,
+            capacity: size,
+        }
+    }
+
+    /// Returns the device pointer to the managed allocation, for passing to a kernel launch.
+    pub fn as_device_ptr(&self) -> ::memory::DevicePointer<T> {
+        unsafe { ::memory::DevicePointer::wrap(self.buf) }
+    }
+
+    /// Prefetch the entire buffer to `device` on the given stream, migrating its pages ahead of
+    /// a kernel launch to avoid page-fault thrashing during the kernel itself.
+    ///
+    /// # Errors:
+    ///
+    /// If the prefetch fails, returns the error from CUDA.
+    pub fn prefetch_to_device(&self, device: &Device, stream: &Stream) -> CudaResult<()> {
+        if self.capacity > 0 {
+            cuda_mem_prefetch_async(self.buf, self.capacity, device, stream)?;
+        }
+        Ok(())
+    }
+
+    /// Prefetch the entire buffer back to the host on the given stream.
+    ///
+    /// # Errors:
+    ///
+    /// If the prefetch fails, returns the error from CUDA.
+    pub fn prefetch_to_host(&self, stream: &Stream) -> CudaResult<()> {
+        if self.capacity > 0 {
+            cuda_mem_prefetch_to_host_async(self.buf, self.capacity, stream)?;
+        }
+        Ok(())
+    }
+
+    /// Advise the CUDA driver about how this buffer will be used. See
+    /// [`MemAdvise`](enum.MemAdvise.html) for the available hints.
+    ///
+    /// # Errors:
+    ///
+    /// If the advise call fails, returns the error from CUDA.
+    pub fn advise(&self, advice: MemAdvise, device: &Device) -> CudaResult<()> {
+        if self.capacity > 0 {
+            cuda_mem_advise(self.buf, self.capacity, advice, device)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: DeviceCopy> AsRef<[T]> for UnifiedBuffer<T> {
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
+impl<T: DeviceCopy> AsMut<[T]> for UnifiedBuffer<T> {
+    fn as_mut(&mut self) -> &mut [T] {
+        self
+    }
+}
+impl<T: DeviceCopy> ops::Deref for UnifiedBuffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe {
+            let p = self.buf;
+            slice::from_raw_parts(p, self.capacity)
+        }
+    }
+}
+impl<T: DeviceCopy> ops::DerefMut for UnifiedBuffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe {
+            let ptr = self.buf;
+            slice::from_raw_parts_mut(ptr, self.capacity)
+        }
+    }
+}
+impl<T: DeviceCopy> Drop for UnifiedBuffer<T> {
+    fn drop(&mut self) {
+        if self.capacity > 0 && mem::size_of::<T>() > 0 {
+            // No choice but to panic if this fails.
+            unsafe {
+                cuda_free_unified(self.buf).expect("Failed to deallocate CUDA unified memory.");
+            }
+        }
+        self.capacity = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct ZeroSizedType;
+    unsafe impl ::memory::DeviceCopy for ZeroSizedType {}
+
+    #[test]
+    fn test_new() {
+        let val = 0u64;
+        let mut buffer = UnifiedBuffer::new(&val, 5).unwrap();
+        buffer[0] = 1;
+    }
+
+    #[test]
+    fn test_from_slice() {
+        let values = [0u64; 10];
+        let mut buffer = UnifiedBuffer::from_slice(&values).unwrap();
+        for i in buffer[0..3].iter_mut() {
+            *i = 10;
+        }
+    }
+
+    #[test]
+    fn zero_length_buffer() {
+        let buffer = UnifiedBuffer::new(&0u64, 0).unwrap();
+        drop(buffer);
+    }
+
+    #[test]
+    fn zero_size_type() {
+        let buffer = UnifiedBuffer::new(&ZeroSizedType, 10).unwrap();
+        drop(buffer);
+    }
+
+    #[test]
+    fn overflows_usize() {
+        let err = UnifiedBuffer::new(&0u64, ::std::usize::MAX - 1).unwrap_err();
+        assert_eq!(CudaError::InvalidMemoryAllocation, err);
+    }
+
+    #[test]
+    fn test_unified_box() {
+        let mut boxed = UnifiedBox::new(5u64).unwrap();
+        *boxed = 10;
+        assert_eq!(10u64, *boxed);
+    }
+
+    #[test]
+    fn test_prefetch_and_advise() {
+        use device::Device;
+        use stream::{Stream, StreamFlags};
+
+        let device = Device::get_device(0).unwrap();
+        let stream = Stream::new(StreamFlags::NON_BLOCKING, None).unwrap();
+        let mut buffer = UnifiedBuffer::new(&0u64, 5).unwrap();
+
+        buffer.advise(MemAdvise::SetPreferredLocation, &device).unwrap();
+        buffer.prefetch_to_device(&device, &stream).unwrap();
+        stream.synchronize().unwrap();
+        buffer[0] = 1;
+        buffer.prefetch_to_host(&stream).unwrap();
+        stream.synchronize().unwrap();
+
+        assert_eq!(1u64, buffer[0]);
+    }
+}